@@ -0,0 +1,248 @@
+//! Implementations of the actual SPI commands used to talk to the flash chip.
+
+use core::fmt::Debug;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::{command_and_address, AddressWidth, Command, Error, W25};
+
+impl<Series: crate::NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> W25<Series, SPI, HOLD, WP>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Poll-count ceiling for a page program, which finishes in well under a
+    /// millisecond per byte.
+    const PAGE_PROGRAM_MAX_POLLS: u32 = 1_000;
+    /// Poll-count ceiling for a single sector erase.
+    const SECTOR_ERASE_MAX_POLLS: u32 = 10_000;
+    /// Poll-count ceiling for a 32 KiB or 64 KiB block erase.
+    const BLOCK_ERASE_MAX_POLLS: u32 = 50_000;
+    /// Poll-count ceiling for a full chip erase, which on large parts can take tens
+    /// of seconds and so gets a much larger budget than any other operation.
+    const CHIP_ERASE_MAX_POLLS: u32 = 2_000_000;
+
+    /// Switch the chip into 4-byte addressing mode.
+    pub(crate) fn enter_4byte_mode(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::Enter4ByteMode as u8])
+            .map_err(Error::SpiError)
+    }
+
+    /// Switch the chip back into 3-byte addressing mode.
+    ///
+    /// This updates the driver's own addressing mode to match, so every command
+    /// issued afterwards goes out with a 3-byte address. On a chip larger than
+    /// 16 MiB this means anything at or above that offset becomes unreachable
+    /// until 4-byte mode is re-entered.
+    pub fn exit_4byte_mode(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::Exit4ByteMode as u8])
+            .map_err(Error::SpiError)?;
+        self.address_width = AddressWidth::ThreeByte;
+        Ok(())
+    }
+
+    /// Read the JEDEC manufacturer and device ID.
+    pub fn read_jedec_id(&mut self) -> Result<[u8; 3], Error<S, P>> {
+        let mut id = [0u8; 3];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[Command::JedecId as u8]),
+                Operation::Read(&mut id),
+            ])
+            .map_err(Error::SpiError)?;
+        Ok(id)
+    }
+
+    /// Read the factory-programmed 64-bit unique ID.
+    pub fn read_unique_id(&mut self) -> Result<[u8; 8], Error<S, P>> {
+        let mut id = [0u8; 8];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[Command::UniqueId as u8, 0, 0, 0, 0]),
+                Operation::Read(&mut id),
+            ])
+            .map_err(Error::SpiError)?;
+        Ok(id)
+    }
+
+    pub(crate) fn read_status_register_1(&mut self) -> Result<u8, Error<S, P>> {
+        let mut status = [0u8; 1];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[Command::ReadStatusRegister1 as u8]),
+                Operation::Read(&mut status),
+            ])
+            .map_err(Error::SpiError)?;
+        Ok(status[0])
+    }
+
+    /// Whether the chip is currently busy with an internal write/erase cycle.
+    ///
+    /// This issues a single status-register read and returns immediately, so callers
+    /// that don't want to block inside the driver can drive completion from their
+    /// own event loop by polling this instead of one of the blocking operations.
+    pub fn is_busy(&mut self) -> Result<bool, Error<S, P>> {
+        Ok(self.read_status_register_1()? & 0x01 != 0)
+    }
+
+    /// Poll the busy bit up to `max_polls` times, returning [`Error::Timeout`] if the
+    /// chip never reports idle. The ceiling should scale with how long the
+    /// operation that was just issued is expected to take.
+    pub(crate) fn wait_while_busy(&mut self, max_polls: u32) -> Result<(), Error<S, P>> {
+        for _ in 0..max_polls {
+            if !self.is_busy()? {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    pub(crate) fn write_enable(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::WriteEnable as u8])
+            .map_err(Error::SpiError)
+    }
+
+    fn frame(&self, command: Command, address: u32) -> crate::CommandFrame {
+        command_and_address(command as u8, address, self.address_width)
+    }
+
+    /// Read `buf.len()` bytes of the SFDP table starting at `address`.
+    ///
+    /// SFDP addresses are always 3 bytes regardless of the chip's normal addressing
+    /// mode, and are followed by a single dummy byte before data starts.
+    fn read_sfdp(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
+        let frame = command_and_address(Command::ReadSfdp as u8, address, AddressWidth::ThreeByte);
+        self.spi
+            .transaction(&mut [
+                Operation::Write(frame.as_slice()),
+                Operation::Write(&[0]),
+                Operation::Read(buf),
+            ])
+            .map_err(Error::SpiError)
+    }
+
+    /// Walk the SFDP parameter headers, find the JEDEC Basic Flash Parameter table,
+    /// and derive the chip's byte capacity from its density DWORD.
+    pub(crate) fn read_sfdp_capacity(&mut self) -> Result<u32, Error<S, P>> {
+        let mut header = [0u8; 8];
+        self.read_sfdp(0, &mut header)?;
+
+        if &header[0..4] != b"SFDP" {
+            return Err(Error::InvalidSfdp);
+        }
+        let nph = header[6];
+
+        for i in 0..=nph as u32 {
+            let mut param_header = [0u8; 8];
+            self.read_sfdp(8 + i * 8, &mut param_header)?;
+
+            let id_lsb = param_header[0];
+            let id_msb = param_header[7];
+            if id_lsb != 0x00 || id_msb != 0xFF {
+                continue;
+            }
+
+            let table_pointer =
+                u32::from_le_bytes([param_header[4], param_header[5], param_header[6], 0]);
+
+            let mut table = [0u8; 8];
+            self.read_sfdp(table_pointer, &mut table)?;
+
+            let density = u32::from_le_bytes([table[4], table[5], table[6], table[7]]);
+            let capacity_bits = if density & 0x8000_0000 == 0 {
+                density + 1
+            } else {
+                1u32.checked_shl(density & 0x7FFF_FFFF)
+                    .ok_or(Error::InvalidSfdp)?
+            };
+
+            return Ok(capacity_bits / 8);
+        }
+
+        Err(Error::InvalidSfdp)
+    }
+
+    pub(crate) fn read_data(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
+        let frame = self.frame(Command::ReadData, address);
+        self.spi
+            .transaction(&mut [Operation::Write(frame.as_slice()), Operation::Read(buf)])
+            .map_err(Error::SpiError)
+    }
+
+    pub(crate) fn page_program(&mut self, address: u32, data: &[u8]) -> Result<(), Error<S, P>> {
+        self.write_enable()?;
+        let frame = self.frame(Command::PageProgram, address);
+        self.spi
+            .transaction(&mut [Operation::Write(frame.as_slice()), Operation::Write(data)])
+            .map_err(Error::SpiError)?;
+        self.wait_while_busy(Self::PAGE_PROGRAM_MAX_POLLS)
+    }
+
+    pub(crate) fn sector_erase(&mut self, address: u32) -> Result<(), Error<S, P>> {
+        self.write_enable()?;
+        let frame = self.frame(Command::SectorErase, address);
+        self.spi.write(frame.as_slice()).map_err(Error::SpiError)?;
+        self.wait_while_busy(Self::SECTOR_ERASE_MAX_POLLS)
+    }
+
+    pub(crate) fn block_erase_32k(&mut self, address: u32) -> Result<(), Error<S, P>> {
+        self.write_enable()?;
+        let frame = self.frame(Command::Block32Erase, address);
+        self.spi.write(frame.as_slice()).map_err(Error::SpiError)?;
+        self.wait_while_busy(Self::BLOCK_ERASE_MAX_POLLS)
+    }
+
+    pub(crate) fn block_erase_64k(&mut self, address: u32) -> Result<(), Error<S, P>> {
+        self.write_enable()?;
+        let frame = self.frame(Command::Block64Erase, address);
+        self.spi.write(frame.as_slice()).map_err(Error::SpiError)?;
+        self.wait_while_busy(Self::BLOCK_ERASE_MAX_POLLS)
+    }
+
+    /// Erase the entire chip. On large parts this can take tens of seconds, so this
+    /// gets a much larger poll-count ceiling than any other operation.
+    pub fn chip_erase(&mut self) -> Result<(), Error<S, P>> {
+        self.write_enable()?;
+        self.spi
+            .write(&[Command::ChipErase as u8])
+            .map_err(Error::SpiError)?;
+        self.wait_while_busy(Self::CHIP_ERASE_MAX_POLLS)
+    }
+
+    /// Put the chip into its low-power power-down state.
+    pub fn power_down(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::PowerDown as u8])
+            .map_err(Error::SpiError)
+    }
+
+    /// Wake the chip back up from the power-down state.
+    pub fn release_power_down(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::ReleasePowerDown as u8])
+            .map_err(Error::SpiError)
+    }
+}
+
+impl<Series: crate::NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> W25<Series, SPI, HOLD, WP>
+where
+    Series: crate::Reset,
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Reset the chip back to its power-on-reset state.
+    pub fn reset(&mut self) -> Result<(), Error<S, P>> {
+        self.spi
+            .write(&[Command::EnableReset as u8])
+            .map_err(Error::SpiError)?;
+        self.spi
+            .write(&[Command::Reset as u8])
+            .map_err(Error::SpiError)
+    }
+}
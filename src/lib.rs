@@ -3,11 +3,24 @@
 #![deny(unsafe_code)]
 #![warn(missing_docs)]
 
+// Won't-implement: Dual/Quad fast-read and quad page-program.
+//
+// `embedded_hal::spi::SpiDevice`/`SpiBus` only model a single MOSI/MISO line, so there
+// is no way to drive the extra IO lines a Dual/Quad opcode's data phase needs. Doing
+// this for real would mean defining a separate multi-IO bus trait and bridging it to a
+// platform HAL's QSPI peripheral, which is a different shape of driver than this one.
+// Until such a trait exists upstream, this crate only speaks single-lane SPI.
+
 use core::{fmt::Debug, marker::PhantomData};
 use embedded_hal::digital::{OutputPin, PinState};
-use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
 
 mod commands_impl;
+mod w25m;
+
+pub use w25m::W25M;
 
 /// The Q series
 pub struct Q;
@@ -54,6 +67,34 @@ enum Command {
     ReleasePowerDown = 0xAB,
     JedecId = 0x9F,
     Reset = 0x99,
+    Enter4ByteMode = 0xB7,
+    Exit4ByteMode = 0xE9,
+    ReadSfdp = 0x5A,
+    DieSelect = 0xC2,
+}
+
+/// The width of the address field sent after a command byte.
+///
+/// Chips larger than 16 MiB cannot be fully addressed with a 3-byte address, so they
+/// need to be switched into 4-byte addressing mode before any addressed command is issued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressWidth {
+    ThreeByte,
+    FourByte,
+}
+
+impl AddressWidth {
+    /// Chips above this capacity need 4-byte addressing to reach their full range.
+    const FOUR_BYTE_THRESHOLD: u32 = 0x0100_0000;
+
+    fn for_capacity(capacity: u32) -> Self {
+        if capacity > Self::FOUR_BYTE_THRESHOLD {
+            AddressWidth::FourByte
+        } else {
+            AddressWidth::ThreeByte
+        }
+    }
+
 }
 
 /// Low level driver for the w25 flash memory chip.
@@ -62,6 +103,7 @@ pub struct W25<Series, SPI, HOLD, WP> {
     hold: HOLD,
     wp: WP,
     capacity: u32,
+    address_width: AddressWidth,
     _pantom: PhantomData<Series>,
 }
 
@@ -84,64 +126,195 @@ impl<Series: NorSeries, SPI, HOLD, WP> W25<Series, SPI, HOLD, WP> {
     }
 }
 
-impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP>
-    W25<Series, SPI, HOLD, WP>
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> W25<Series, SPI, HOLD, WP>
 where
-    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Set the hold pin state.
+    ///
+    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
+    /// By default this means the pin needs to be high (true).
+    ///
+    /// This function sets the pin directly and can cause the chip to not work.
+    pub fn set_hold(&mut self, value: PinState) -> Result<(), Error<S, P>> {
+        self.hold.set_state(value).map_err(Error::PinError)?;
+        Ok(())
+    }
+
+    /// Set the write protect pin state.
+    ///
+    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
+    /// By default this means the pin needs to be high (true).
+    ///
+    /// This function sets the pin directly and can cause the chip to not work.
+    pub fn set_wp(&mut self, value: PinState) -> Result<(), Error<S, P>> {
+        self.wp.set_state(value).map_err(Error::PinError)?;
+        Ok(())
+    }
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> W25<Series, SPI, HOLD, WP>
+where
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
     HOLD: OutputPin<Error = P>,
     WP: OutputPin<Error = P>,
 {
     /// Create a new instance of the flash.
-    /// 
+    ///
     /// The capacity must be the total chip capacity.
     /// Weird things can happen if you provide the wrong value.
     /// No checks are done, you're believed at your word.
+    ///
+    /// Chips whose capacity exceeds 16 MiB are automatically switched into 4-byte
+    /// addressing mode, since a 3-byte address cannot reach their full range.
     pub fn new(spi: SPI, hold: HOLD, wp: WP, capacity: u32) -> Result<Self, Error<S, P>> {
         let mut flash = W25 {
             spi,
             hold,
             wp,
             capacity,
+            address_width: AddressWidth::for_capacity(capacity),
             _pantom: PhantomData,
         };
 
         flash.hold.set_high().map_err(Error::PinError)?;
         flash.wp.set_high().map_err(Error::PinError)?;
 
+        if flash.address_width == AddressWidth::FourByte {
+            flash.enter_4byte_mode()?;
+        }
+
         Ok(flash)
     }
 
-    /// Set the hold pin state.
-    ///
-    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
-    /// By default this means the pin needs to be high (true).
+    /// Create a new instance of the flash, auto-detecting its capacity from the
+    /// Serial Flash Discoverable Parameters (SFDP) table instead of trusting a
+    /// user-supplied value.
     ///
-    /// This function sets the pin directly and can cause the chip to not work.
-    pub fn set_hold(&mut self, value: PinState) -> Result<(), Error<S, P>> {
-        self.hold.set_state(value).map_err(Error::PinError)?;
+    /// This reads the JEDEC Basic Flash Parameter table out of SFDP and derives the
+    /// chip's byte capacity from its density field. Returns [`Error::InvalidSfdp`] if
+    /// the SFDP signature can't be read back, if no basic flash parameter table
+    /// is advertised, or if its density field gives a bit count too large for a `u32`.
+    pub fn from_sfdp(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S, P>> {
+        let mut flash = W25 {
+            spi,
+            hold,
+            wp,
+            capacity: 0,
+            address_width: AddressWidth::ThreeByte,
+            _pantom: PhantomData,
+        };
+
+        flash.hold.set_high().map_err(Error::PinError)?;
+        flash.wp.set_high().map_err(Error::PinError)?;
+
+        flash.capacity = flash.read_sfdp_capacity()?;
+        flash.address_width = AddressWidth::for_capacity(flash.capacity);
+
+        if flash.address_width == AddressWidth::FourByte {
+            flash.enter_4byte_mode()?;
+        }
+
+        Ok(flash)
+    }
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> ErrorType for W25<Series, SPI, HOLD, WP>
+where
+    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    type Error = Error<S, P>;
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> ReadNorFlash
+    for W25<Series, SPI, HOLD, WP>
+where
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let end = offset
+            .checked_add(bytes.len() as u32)
+            .ok_or(Error::OutOfBounds)?;
+        if end > self.capacity {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.read_data(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> NorFlash for W25<Series, SPI, HOLD, WP>
+where
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = Series::SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(Series::SECTOR_SIZE) || !to.is_multiple_of(Series::SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if to > self.capacity {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut address = from;
+        while address < to {
+            self.sector_erase(address)?;
+            address += Series::SECTOR_SIZE;
+        }
+
         Ok(())
     }
 
-    /// Set the write protect pin state.
-    ///
-    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
-    /// By default this means the pin needs to be high (true).
-    ///
-    /// This function sets the pin directly and can cause the chip to not work.
-    pub fn set_wp(&mut self, value: PinState) -> Result<(), Error<S, P>> {
-        self.wp.set_state(value).map_err(Error::PinError)?;
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let end = offset
+            .checked_add(bytes.len() as u32)
+            .ok_or(Error::OutOfBounds)?;
+        if end > self.capacity {
+            return Err(Error::OutOfBounds);
+        }
+
+        let mut address = offset;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let page_offset = address % Series::PAGE_SIZE;
+            let chunk_len = (Series::PAGE_SIZE - page_offset).min(remaining.len() as u32) as usize;
+            let (chunk, rest) = remaining.split_at(chunk_len);
+
+            self.page_program(address, chunk)?;
+
+            address += chunk_len as u32;
+            remaining = rest;
+        }
+
         Ok(())
     }
 }
 
-impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> ErrorType
+/// NOR flash allows individual bits to be rewritten from 1 to 0 without a prior
+/// erase, so repeated [`NorFlash::write`] calls over the same region are safe.
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP> MultiwriteNorFlash
     for W25<Series, SPI, HOLD, WP>
 where
-    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    SPI: embedded_hal::spi::SpiDevice<Error = S>,
     HOLD: OutputPin<Error = P>,
     WP: OutputPin<Error = P>,
 {
-    type Error = Error<S, P>;
 }
 
 /// Custom error type for the various errors that can be thrown by driver.
@@ -160,6 +333,13 @@ pub enum Error<S: Debug, P: Debug> {
     OutOfBounds,
     /// Setting the write enable bit failed for some reason
     WriteEnableFail,
+    /// A blocking operation polled the status register's busy bit until it hit its
+    /// poll-count ceiling without the chip ever reporting idle.
+    Timeout,
+    /// The SFDP table could not be read: the signature didn't spell "SFDP", no
+    /// JEDEC Basic Flash Parameter table was advertised, or its density field gave
+    /// a bit count too large for a `u32`.
+    InvalidSfdp,
 }
 
 impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
@@ -172,13 +352,43 @@ impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
     }
 }
 
+/// A command byte followed by a width-appropriate big-endian address, ready to be
+/// written to the bus. Only the first `len` bytes of `buf` are valid.
+struct CommandFrame {
+    buf: [u8; 5],
+    len: usize,
+}
+
+impl CommandFrame {
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
 #[allow(clippy::identity_op)]
-fn command_and_address(command: u8, address: u32) -> [u8; 4] {
-    [
-        command,
-        // MSB, BE
-        ((address & 0xFF0000) >> 16) as u8,
-        ((address & 0x00FF00) >> 8) as u8,
-        ((address & 0x0000FF) >> 0) as u8,
-    ]
+fn command_and_address(command: u8, address: u32, width: AddressWidth) -> CommandFrame {
+    match width {
+        AddressWidth::ThreeByte => CommandFrame {
+            buf: [
+                command,
+                // MSB, BE
+                ((address & 0xFF0000) >> 16) as u8,
+                ((address & 0x00FF00) >> 8) as u8,
+                ((address & 0x0000FF) >> 0) as u8,
+                0,
+            ],
+            len: 4,
+        },
+        AddressWidth::FourByte => CommandFrame {
+            buf: [
+                command,
+                // MSB, BE
+                ((address & 0xFF00_0000) >> 24) as u8,
+                ((address & 0x00FF_0000) >> 16) as u8,
+                ((address & 0x0000_FF00) >> 8) as u8,
+                ((address & 0x0000_00FF) >> 0) as u8,
+            ],
+            len: 5,
+        },
+    }
 }
@@ -0,0 +1,185 @@
+//! A composite wrapper for W25M-style stacked-die packages.
+//!
+//! Stacked packages present several identical dies behind one chip-select; only one
+//! die is addressable at a time, and switching between them means issuing a
+//! software die-select command. [`W25M`] hides that behind one contiguous address
+//! space spanning all the stacked dies.
+
+use core::fmt::Debug;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiDevice;
+use embedded_storage::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+use crate::{Command, Error, NorSeries, W25};
+
+/// Wrapper for `W25M`-style stacked-die packages, presenting `N` identical dies as
+/// one contiguous address space.
+///
+/// Only one die can be active on the shared SPI/HOLD/WP lines at a time, so `W25M`
+/// issues the software die-select command (`0xC2`) whenever an operation targets a
+/// different die than the one currently selected.
+pub struct W25M<Series, SPI, HOLD, WP, const N: usize> {
+    die: W25<Series, SPI, HOLD, WP>,
+    current_die: u8,
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP, const N: usize>
+    W25M<Series, SPI, HOLD, WP, N>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    /// Wrap a single die's driver into an `N`-die stacked composite.
+    ///
+    /// `die` must have been constructed with the capacity of a *single* die; the
+    /// composite's total capacity (see [`W25M::capacity`]) is `N` times that.
+    pub fn new(die: W25<Series, SPI, HOLD, WP>) -> Self {
+        W25M {
+            die,
+            current_die: 0,
+        }
+    }
+
+    /// Total capacity across all stacked dies, in bytes.
+    pub fn capacity(&self) -> u32 {
+        self.die.capacity() * N as u32
+    }
+
+    /// Split a linear address into a die index and a local address within that die.
+    fn locate(&self, address: u32) -> (u8, u32) {
+        let die_capacity = self.die.capacity();
+        ((address / die_capacity) as u8, address % die_capacity)
+    }
+
+    fn select_die(&mut self, die: u8) -> Result<(), Error<S, P>> {
+        if die != self.current_die {
+            self.die
+                .spi
+                .write(&[Command::DieSelect as u8, die])
+                .map_err(Error::SpiError)?;
+            self.current_die = die;
+        }
+        Ok(())
+    }
+
+    /// Check that `[address, address + len)` lies within the composite address
+    /// space and doesn't cross a die boundary, returning the die and local address
+    /// to use.
+    fn locate_checked(&self, address: u32, len: u32) -> Result<(u8, u32), Error<S, P>> {
+        match address.checked_add(len) {
+            Some(end) if end <= self.capacity() => {}
+            _ => return Err(Error::OutOfBounds),
+        }
+
+        let (die, local_address) = self.locate(address);
+        let die_capacity = self.die.capacity();
+        if local_address + len > die_capacity {
+            return Err(Error::OutOfBounds);
+        }
+
+        Ok((die, local_address))
+    }
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP, const N: usize> ErrorType
+    for W25M<Series, SPI, HOLD, WP, N>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    type Error = Error<S, P>;
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP, const N: usize> ReadNorFlash
+    for W25M<Series, SPI, HOLD, WP, N>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let die_capacity = self.die.capacity();
+        let mut address = offset;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let chunk_len = (die_capacity - address % die_capacity).min(remaining.len() as u32);
+            let (chunk, rest) = core::mem::take(&mut remaining).split_at_mut(chunk_len as usize);
+
+            let (die, local_address) = self.locate_checked(address, chunk_len)?;
+            self.select_die(die)?;
+            self.die.read_data(local_address, chunk)?;
+
+            address += chunk_len;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        W25M::capacity(self) as usize
+    }
+}
+
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP, const N: usize> NorFlash
+    for W25M<Series, SPI, HOLD, WP, N>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = Series::SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(Series::SECTOR_SIZE) || !to.is_multiple_of(Series::SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+
+        let mut address = from;
+        while address < to {
+            let (die, local_address) = self.locate_checked(address, Series::SECTOR_SIZE)?;
+            self.select_die(die)?;
+            self.die.sector_erase(local_address)?;
+            address += Series::SECTOR_SIZE;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let mut address = offset;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let page_offset = address % Series::PAGE_SIZE;
+            let chunk_len = (Series::PAGE_SIZE - page_offset).min(remaining.len() as u32);
+            let (chunk, rest) = remaining.split_at(chunk_len as usize);
+
+            let (die, local_address) = self.locate_checked(address, chunk_len)?;
+            self.select_die(die)?;
+            self.die.page_program(local_address, chunk)?;
+
+            address += chunk_len;
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+}
+
+/// NOR flash allows individual bits to be rewritten from 1 to 0 without a prior
+/// erase, so repeated [`NorFlash::write`] calls over the same region are safe.
+/// Stacking dies behind one address space doesn't change that property.
+impl<Series: NorSeries, SPI, S: Debug, P: Debug, HOLD, WP, const N: usize> MultiwriteNorFlash
+    for W25M<Series, SPI, HOLD, WP, N>
+where
+    SPI: SpiDevice<Error = S>,
+    HOLD: OutputPin<Error = P>,
+    WP: OutputPin<Error = P>,
+{
+}